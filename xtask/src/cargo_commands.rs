@@ -2,12 +2,60 @@ use crate::{
     argument_parsing::{
         Backends, BuildOrCheck, ExtraArguments, Globals, Package, PackageOpt, TestMetadata,
     },
-    command::{BuildMode, CargoCommand},
-    command_parser, package_feature_extractor,
+    command::{self, BuildMode, CargoCommand},
+    command_parser,
+    dep_queue::{DependencyQueue, NodeId},
+    fingerprint, package_feature_extractor,
+    size_db::{self, SizeCollector},
+    udeps::{self, UnusedDependency},
+    ui_test,
 };
 use log::error;
 use rayon::prelude::*;
 
+/// One scheduled unit of work in a [`DependencyQueue`]-driven pipeline,
+/// together with whatever flag that particular command needs out of
+/// `command_parser`'s `overwrite` parameter.
+enum PipelineStep<'c> {
+    Test(CargoCommand<'c>),
+    Build(CargoCommand<'c>),
+    Qemu(CargoCommand<'c>, bool),
+    /// `command`'s captured stdout is parsed into per-section sizes and
+    /// pushed onto `size_collector`, labeled with `example`/`backend`/`target`.
+    Size {
+        command: CargoCommand<'c>,
+        example: String,
+        backend: String,
+        target: String,
+    },
+}
+
+fn run_pipeline_step(
+    globals: &Globals,
+    step: PipelineStep,
+    size_collector: &SizeCollector,
+) -> anyhow::Result<()> {
+    match step {
+        PipelineStep::Test(cmd) => command_parser(globals, &cmd, false),
+        PipelineStep::Build(cmd) => command_parser(globals, &cmd, false),
+        PipelineStep::Qemu(cmd, overwrite) => command_parser(globals, &cmd, overwrite),
+        PipelineStep::Size {
+            command: cmd,
+            example,
+            backend,
+            target,
+        } => {
+            let output = command::command_parser_captured(globals, &cmd)?;
+            for (section, bytes) in size_db::parse_sections(&output) {
+                size_collector.push(size_db::SizeRecord::new(
+                    &example, &backend, &target, &section, bytes,
+                ));
+            }
+            Ok(())
+        }
+    }
+}
+
 /// Cargo command to either build or check
 pub fn cargo(
     globals: &Globals,
@@ -42,14 +90,54 @@ pub fn cargo(
 /// Cargo command to either build or check all examples
 ///
 /// The examples are in rtic/examples
+///
+/// With `changed_only`, an example is skipped when its fingerprint (its own
+/// source plus every shared workspace crate's sources) hasn't changed since
+/// the last successful run recorded in the on-disk fingerprint cache.
 pub fn cargo_example(
     globals: &Globals,
     operation: BuildOrCheck,
     cargoarg: &Option<&str>,
     backend: Backends,
     examples: &[String],
+    changed_only: bool,
 ) -> anyhow::Result<()> {
-    examples.into_par_iter().for_each(|example| {
+    let feature_key = format!("{backend:?}");
+    let cache_kind = match operation {
+        BuildOrCheck::Check => "check",
+        BuildOrCheck::Build => "build",
+    };
+    let cache_path = fingerprint::default_cache_path();
+    let cache = fingerprint::FingerprintCache::load(&cache_path);
+
+    let selected: Vec<&String> = examples
+        .iter()
+        .filter(|example| {
+            if !changed_only {
+                return true;
+            }
+            let fingerprint = fingerprint::fingerprint_example(
+                &fingerprint::example_source_path(example),
+                &feature_key,
+            );
+            !cache.is_unchanged(cache_kind, example, &fingerprint)
+        })
+        .collect();
+
+    if changed_only {
+        log::info!(
+            "--changed-only: {}/{} examples changed",
+            selected.len(),
+            examples.len()
+        );
+    }
+
+    // Workers record their own example's new fingerprint as they finish, and
+    // the cache is flushed once afterwards instead of every worker locking
+    // the store.
+    let cache = std::sync::Mutex::new(cache);
+
+    selected.into_par_iter().for_each(|example| {
         let features = Some(backend.to_target().and_features(backend.to_rtic_feature()));
 
         let command = match operation {
@@ -69,11 +157,29 @@ pub fn cargo_example(
             },
         };
 
-        if let Err(err) = command_parser(globals, &command, false) {
-            error!("{err}");
+        match command_parser(globals, &command, false) {
+            Ok(()) if changed_only => {
+                let fingerprint = fingerprint::fingerprint_example(
+                    &fingerprint::example_source_path(example),
+                    &feature_key,
+                );
+                cache
+                    .lock()
+                    .expect("fingerprint cache mutex poisoned")
+                    .record(cache_kind, example, &fingerprint);
+            }
+            Ok(()) => {}
+            Err(err) => error!("{err}"),
         }
     });
 
+    if changed_only {
+        cache
+            .into_inner()
+            .expect("fingerprint cache mutex poisoned")
+            .save(&cache_path)?;
+    }
+
     Ok(())
 }
 
@@ -151,7 +257,10 @@ pub fn cargo_test(
         let cmd = TestMetadata::match_package(package, backend);
         command_parser(globals, &cmd, false)?;
     } else {
-        // Iterate over all workspace packages
+        // Workspace packages don't depend on one another's test runs, so they
+        // all go into the queue with no edges between them and run as
+        // concurrency allows, instead of one after another.
+        let mut queue = DependencyQueue::new();
         for package in [
             Package::Rtic,
             Package::RticCommon,
@@ -160,19 +269,177 @@ pub fn cargo_test(
             Package::RticSync,
             Package::RticTime,
         ] {
-            let mut error_messages = vec![];
-            let cmd = &TestMetadata::match_package(package, backend);
-            if let Err(err) = command_parser(globals, cmd, false) {
-                error_messages.push(err);
-            }
+            queue.add_node(PipelineStep::Test(TestMetadata::match_package(
+                package, backend,
+            )));
+        }
 
-            if !error_messages.is_empty() {
-                for err in error_messages {
-                    error!("{err}");
-                }
-            }
+        // No Size step is ever scheduled here, so nothing is ever collected.
+        let size_collector = SizeCollector::new();
+        for (_, err) in
+            queue.run_to_completion(|step| run_pipeline_step(globals, step, &size_collector))
+        {
+            error!("{err}");
+        }
+    }
+    Ok(())
+}
+
+/// Run a check build with an extern-recording `RUSTC_WORKSPACE_WRAPPER` and
+/// report declared dependencies that were never referenced, per workspace
+/// package (or a single package, or per example when `examples` is given).
+///
+/// Exits non-zero when `deny_unused` is set and any unused dependency was
+/// found.
+pub fn cargo_udeps(
+    globals: &Globals,
+    cargoarg: &Option<&str>,
+    package: &PackageOpt,
+    backend: Backends,
+    examples: &[String],
+    deny_unused: bool,
+) -> anyhow::Result<()> {
+    let target = backend.to_target();
+    let log_path = std::env::temp_dir().join("rtic-xtask-udeps.log");
+
+    let packages = match package.package {
+        Some(package) => vec![package],
+        None => vec![
+            Package::Rtic,
+            Package::RticCommon,
+            Package::RticMacros,
+            Package::RticMonotonics,
+            Package::RticSync,
+            Package::RticTime,
+        ],
+    };
+
+    let mut all_unused: Vec<UnusedDependency> = vec![];
+
+    for package in packages {
+        let package_opt = PackageOpt {
+            package: Some(package),
+        };
+        let features = package_feature_extractor(target, &package_opt, backend);
+
+        // The wrapper appends; truncate so this package's `used` set isn't
+        // polluted by whatever a previous package (or a stale prior run)
+        // left behind.
+        udeps::truncate_log(&log_path)?;
+        let cmd = CargoCommand::Udeps {
+            cargoarg,
+            package,
+            target,
+            features,
+            log_path: log_path.clone(),
+        };
+        if let Err(err) = command_parser(globals, &cmd, false) {
+            error!("{err}");
+            continue;
+        }
+
+        let used = udeps::used_externs(&log_path)?;
+        let unused =
+            udeps::unused_for_package(&format!("{package:?}"), &manifest_path_of(package), &used)?;
+        all_unused.extend(unused);
+    }
+
+    for example in examples {
+        // Each example gets its own check build (and its own truncated log),
+        // so `used` reflects only the externs that example's unit actually
+        // references instead of the whole rtic package's.
+        udeps::truncate_log(&log_path)?;
+        let cmd = CargoCommand::ExampleUdeps {
+            cargoarg,
+            example,
+            target,
+            features: Some(target.and_features(backend.to_rtic_feature())),
+            log_path: log_path.clone(),
+        };
+        if let Err(err) = command_parser(globals, &cmd, false) {
+            error!("{err}");
+            continue;
+        }
+
+        let used = udeps::used_externs(&log_path)?;
+        let unused = udeps::unused_for_package(example, &manifest_path_of(Package::Rtic), &used)?;
+        all_unused.extend(unused);
+    }
+
+    if all_unused.is_empty() {
+        log::info!("No unused dependencies found");
+    } else {
+        for unused in &all_unused {
+            log::warn!(
+                "{}: declared dependency `{}` is never used",
+                unused.package,
+                unused.dependency
+            );
+        }
+        if deny_unused {
+            anyhow::bail!("{} unused dependenc(y/ies) found", all_unused.len());
         }
     }
+
+    Ok(())
+}
+
+/// Path to the `Cargo.toml` that declares `package`'s dependencies, relative
+/// to the workspace root.
+fn manifest_path_of(package: Package) -> std::path::PathBuf {
+    let dir = match package {
+        Package::Rtic => "rtic",
+        Package::RticCommon => "rtic-common",
+        Package::RticMacros => "rtic-macros",
+        Package::RticMonotonics => "rtic-monotonics",
+        Package::RticSync => "rtic-sync",
+        Package::RticTime => "rtic-time",
+    };
+    std::path::Path::new(dir).join("Cargo.toml")
+}
+
+/// Compile every UI test under `tests/cfail` and check its captured stderr,
+/// both against inline `//~ ERROR` annotations and a sibling `.stderr`
+/// snapshot. Pass `bless` to (re)write the snapshots instead of failing on a
+/// mismatch.
+pub fn cargo_test_ui(
+    globals: &Globals,
+    cargoarg: &Option<&str>,
+    bless: bool,
+) -> anyhow::Result<()> {
+    let ui_dir = std::path::Path::new("tests/cfail");
+
+    let mut test_files: Vec<_> = std::fs::read_dir(ui_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("rs"))
+        .collect();
+    test_files.sort();
+
+    let mut all_failures = vec![];
+
+    for path in &test_files {
+        let cmd = CargoCommand::TestUi { cargoarg, path };
+        let stderr = match command::command_parser_captured_stderr(globals, &cmd) {
+            Ok(stderr) => stderr,
+            Err(err) => {
+                error!("{err}");
+                continue;
+            }
+        };
+
+        let source = std::fs::read_to_string(path)?;
+        all_failures.extend(ui_test::run_ui_test(path, &source, &stderr, bless)?);
+    }
+
+    for failure in &all_failures {
+        error!("{failure}");
+    }
+
+    if !bless && !all_failures.is_empty() {
+        anyhow::bail!("{} UI test failure(s)", all_failures.len());
+    }
+
     Ok(())
 }
 
@@ -197,39 +464,98 @@ pub fn run_test(
     backend: Backends,
     examples: &[String],
     overwrite: bool,
+    changed_only: bool,
 ) -> anyhow::Result<()> {
     let target = backend.to_target();
     let features = Some(target.and_features(backend.to_rtic_feature()));
+    let feature_key = format!("{backend:?}");
+
+    let cache_path = fingerprint::default_cache_path();
+    let mut cache = fingerprint::FingerprintCache::load(&cache_path);
+
+    let selected: Vec<&String> = examples
+        .iter()
+        .filter(|example| {
+            if !changed_only {
+                return true;
+            }
+            let fingerprint = fingerprint::fingerprint_example(
+                &fingerprint::example_source_path(example),
+                &feature_key,
+            );
+            !cache.is_unchanged("qemu", example, &fingerprint)
+        })
+        .collect();
+
+    if changed_only {
+        log::info!(
+            "--changed-only: running {}/{} examples",
+            selected.len(),
+            examples.len()
+        );
+    }
 
-    examples.into_par_iter().for_each(|example| {
-        let cmd = CargoCommand::ExampleBuild {
+    // Each example's Qemu run only depends on that example's own build, not
+    // on any other example, so the queue lets a fast example start running
+    // under Qemu while slower examples are still building.
+    let mut queue = DependencyQueue::new();
+    let mut example_nodes = vec![];
+    for example in selected.iter().copied() {
+        let build = queue.add_node(PipelineStep::Build(CargoCommand::ExampleBuild {
             cargoarg: &Some("--quiet"),
             example,
             target,
             features: features.clone(),
             mode: BuildMode::Release,
-        };
-        if let Err(err) = command_parser(globals, &cmd, false) {
-            error!("{err}");
-        }
+        }));
+        let qemu = queue.add_node(PipelineStep::Qemu(
+            CargoCommand::Qemu {
+                cargoarg,
+                example,
+                target,
+                features: features.clone(),
+                mode: BuildMode::Release,
+            },
+            overwrite,
+        ));
+        queue.add_dependency(qemu, build, "example binary");
+        example_nodes.push((example, build, qemu));
+    }
 
-        let cmd = CargoCommand::Qemu {
-            cargoarg,
-            example,
-            target,
-            features: features.clone(),
-            mode: BuildMode::Release,
-        };
+    let size_collector = SizeCollector::new();
+    let failures =
+        queue.run_to_completion(|step| run_pipeline_step(globals, step, &size_collector));
+    let failed: std::collections::HashSet<NodeId> = failures.iter().map(|(id, _)| *id).collect();
 
-        if let Err(err) = command_parser(globals, &cmd, overwrite) {
-            error!("{err}");
+    if changed_only {
+        // A failed build never returns `Err` from the Qemu node itself (it
+        // still "finishes" so the rest of the graph drains), so recording
+        // must check both nodes: the build node failing must not be masked
+        // by the qemu node coming back clean against a stale binary.
+        for (example, build, qemu) in &example_nodes {
+            if !failed.contains(build) && !failed.contains(qemu) {
+                let fingerprint = fingerprint::fingerprint_example(
+                    &fingerprint::example_source_path(example),
+                    &feature_key,
+                );
+                cache.record("qemu", example, &fingerprint);
+            }
         }
-    });
+        cache.save(&cache_path)?;
+    }
+
+    for (_, err) in failures {
+        error!("{err}");
+    }
 
     Ok(())
 }
 
-/// Check the binary sizes of examples
+/// Check the binary sizes of examples against their previously recorded
+/// sizes, failing when growth exceeds the threshold passed via `arguments`
+/// (`--deny-size-growth-abs=<bytes>` / `--deny-size-growth-pct=<percent>`).
+/// Diffs against the most recent sample by default, or a specific commit's
+/// sample when `arguments` carries `--baseline-commit=<sha>`.
 pub fn build_and_check_size(
     globals: &Globals,
     cargoarg: &Option<&str>,
@@ -239,32 +565,62 @@ pub fn build_and_check_size(
 ) -> anyhow::Result<()> {
     let target = backend.to_target();
     let features = Some(target.and_features(backend.to_rtic_feature()));
+    let backend_name = format!("{backend:?}");
+    let target_name = format!("{target:?}");
 
-    examples.into_par_iter().for_each(|example| {
-        // Make sure the requested example(s) are built
-        let cmd = CargoCommand::ExampleBuild {
+    // Same rationale as run_test: an example's size report only depends on
+    // that example's own build.
+    let mut queue = DependencyQueue::new();
+    for example in examples {
+        let build = queue.add_node(PipelineStep::Build(CargoCommand::ExampleBuild {
             cargoarg: &Some("--quiet"),
             example,
             target,
             features: features.clone(),
             mode: BuildMode::Release,
-        };
-        if let Err(err) = command_parser(globals, &cmd, false) {
-            error!("{err}");
-        }
+        }));
+        let size = queue.add_node(PipelineStep::Size {
+            command: CargoCommand::ExampleSize {
+                cargoarg,
+                example,
+                target: backend.to_target(),
+                features: features.clone(),
+                mode: BuildMode::Release,
+                arguments: size_db::strip_control_flags(arguments),
+            },
+            example: example.clone(),
+            backend: backend_name.clone(),
+            target: target_name.clone(),
+        });
+        queue.add_dependency(size, build, "example binary");
+    }
 
-        let cmd = CargoCommand::ExampleSize {
-            cargoarg,
-            example,
-            target: backend.to_target(),
-            features: features.clone(),
-            mode: BuildMode::Release,
-            arguments: arguments.clone(),
-        };
-        if let Err(err) = command_parser(globals, &cmd, false) {
-            error!("{err}");
-        }
-    });
+    // Accumulated in memory across the concurrent pipeline, then flushed
+    // once below so workers never contend on the history file's lock.
+    let size_collector = SizeCollector::new();
+    for (_, err) in
+        queue.run_to_completion(|step| run_pipeline_step(globals, step, &size_collector))
+    {
+        error!("{err}");
+    }
+
+    let threshold = size_db::SizeThreshold::from_extra_arguments(arguments);
+    let baseline = size_db::Baseline::from_extra_arguments(arguments);
+    let history_path = size_db::default_history_path();
+    let mut history = size_db::SizeHistory::load(&history_path)?;
+    let deltas = size_db::record_and_diff(
+        &mut history,
+        size_collector.into_records(),
+        &threshold,
+        &baseline,
+    );
+
+    size_db::print_table(&deltas);
+    history.save(&history_path)?;
+
+    if deltas.iter().any(|delta| delta.is_regression) {
+        anyhow::bail!("binary size regression exceeds the configured threshold");
+    }
 
     Ok(())
 }
@@ -0,0 +1,140 @@
+//! Readiness-based scheduler for pipelining dependent cargo invocations.
+//!
+//! `run_test` and `build_and_check_size` used to serialize a build and a
+//! follow-up step (Qemu, size) inside one `into_par_iter` closure per
+//! example, with no coordination across examples or workspace packages.
+//! [`DependencyQueue`] models each `CargoCommand` invocation as a node and
+//! each "needs this artifact from that node" relationship as an edge, so a
+//! node becomes ready the instant every node it depends on has finished,
+//! regardless of what else is still running.
+
+use std::sync::mpsc;
+
+/// Opaque handle to a node registered in a [`DependencyQueue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+struct Node<N, E> {
+    value: Option<N>,
+    /// Number of not-yet-finished dependencies.
+    unresolved: usize,
+    /// Nodes that depend on this one, with the artifact edge they're waiting on.
+    dependents: Vec<(NodeId, E)>,
+}
+
+/// A DAG of work items where a node becomes ready to run once every node it
+/// depends on has finished.
+pub struct DependencyQueue<N, E> {
+    nodes: Vec<Node<N, E>>,
+}
+
+impl<N, E: Clone> DependencyQueue<N, E> {
+    pub fn new() -> Self {
+        Self { nodes: Vec::new() }
+    }
+
+    /// Register a node and return a handle to it.
+    pub fn add_node(&mut self, value: N) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            value: Some(value),
+            unresolved: 0,
+            dependents: Vec::new(),
+        });
+        id
+    }
+
+    /// Record that `node` cannot run until `depends_on` has finished,
+    /// because it needs `edge` (the artifact `depends_on` produces).
+    pub fn add_dependency(&mut self, node: NodeId, depends_on: NodeId, edge: E) {
+        self.nodes[node.0].unresolved += 1;
+        self.nodes[depends_on.0].dependents.push((node, edge));
+    }
+
+    /// Every node with no unresolved dependencies, removing their values
+    /// from the queue.
+    fn take_ready(&mut self) -> Vec<(NodeId, N)> {
+        self.nodes
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, node)| node.unresolved == 0)
+            .filter_map(|(idx, node)| node.value.take().map(|value| (NodeId(idx), value)))
+            .collect()
+    }
+
+    /// Mark `id` finished and return the dependents that just became ready,
+    /// taking their values out of the queue.
+    fn finish(&mut self, id: NodeId) -> Vec<(NodeId, N)> {
+        let dependents = self.nodes[id.0].dependents.clone();
+        let mut newly_ready = vec![];
+        for (dependent, _edge) in dependents {
+            let node = &mut self.nodes[dependent.0];
+            node.unresolved -= 1;
+            if node.unresolved == 0 {
+                if let Some(value) = node.value.take() {
+                    newly_ready.push((dependent, value));
+                }
+            }
+        }
+        newly_ready
+    }
+
+    /// Run every node to completion via `run`, respecting dependency order.
+    /// Nodes with no outstanding dependency run concurrently on rayon's
+    /// thread pool; a node's failure is recorded but does not block its
+    /// unrelated siblings, only its own dependents (which never become
+    /// ready since their edge count never reaches zero... unless `run`
+    /// treats the dependency as best-effort, which is the caller's choice:
+    /// here a failed node still "finishes" so the rest of the graph drains).
+    ///
+    /// Returns failures paired with the node that produced them, sorted by
+    /// [`NodeId`] so aggregated error output stays deterministic across runs
+    /// despite nodes finishing in whatever order the thread pool schedules
+    /// them.
+    pub fn run_to_completion<F>(mut self, run: F) -> Vec<(NodeId, anyhow::Error)>
+    where
+        N: Send,
+        F: Fn(N) -> anyhow::Result<()> + Sync,
+    {
+        let total = self.nodes.len();
+        let mut finished = 0;
+        let mut failures = Vec::new();
+
+        let (tx, rx) = mpsc::channel::<(NodeId, anyhow::Result<()>)>();
+
+        rayon::scope(|scope| {
+            let dispatch = |scope: &rayon::Scope, ready: Vec<(NodeId, N)>| {
+                for (id, value) in ready {
+                    let tx = tx.clone();
+                    let run = &run;
+                    scope.spawn(move |_| {
+                        let result = run(value);
+                        let _ = tx.send((id, result));
+                    });
+                }
+            };
+
+            dispatch(scope, self.take_ready());
+
+            while finished < total {
+                let (id, result) = rx
+                    .recv()
+                    .expect("a spawned node dropped its sender without finishing");
+                finished += 1;
+                if let Err(err) = result {
+                    failures.push((id, err));
+                }
+                dispatch(scope, self.finish(id));
+            }
+        });
+
+        failures.sort_by_key(|(id, _)| *id);
+        failures
+    }
+}
+
+impl<N, E: Clone> Default for DependencyQueue<N, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,121 @@
+//! Fingerprint cache backing `--changed-only` in [`crate::cargo_commands::cargo_example`]
+//! and [`crate::cargo_commands::run_test`], so incremental local iteration
+//! only rebuilds/reruns the examples an edit actually affects.
+//!
+//! An example's fingerprint folds in its own source file, the selected
+//! backend, and the sources of every workspace crate it shares with the rest
+//! of the suite (`rtic`, `rtic-macros`, `rtic-sync`, `rtic-monotonics`, ...).
+//! Editing one example invalidates only that example; editing a shared crate
+//! invalidates all of them.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Workspace crates every example transitively depends on.
+const SHARED_CRATES: &[&str] = &[
+    "rtic",
+    "rtic-common",
+    "rtic-macros",
+    "rtic-monotonics",
+    "rtic-sync",
+    "rtic-time",
+];
+
+/// Persisted `"<kind>:<example>" -> last-seen fingerprint` map.
+///
+/// `kind` namespaces the cache by what was actually done to the example
+/// (`"check"`, `"build"`, `"qemu"`, ...): checking an example doesn't prove
+/// it builds, and building it doesn't prove it passed under Qemu, so each
+/// operation must invalidate and re-run independently of the others even
+/// when the example's sources haven't changed since some *other* operation
+/// last touched it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FingerprintCache {
+    fingerprints: HashMap<String, String>,
+}
+
+/// Default location of the cache, at the workspace root.
+pub fn default_cache_path() -> PathBuf {
+    PathBuf::from(".xtask-fingerprints.json")
+}
+
+fn cache_key(kind: &str, example: &str) -> String {
+    format!("{kind}:{example}")
+}
+
+impl FingerprintCache {
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Whether `example`'s last recorded fingerprint for `kind` is still
+    /// `fingerprint`.
+    pub fn is_unchanged(&self, kind: &str, example: &str, fingerprint: &str) -> bool {
+        self.fingerprints
+            .get(&cache_key(kind, example))
+            .is_some_and(|previous| previous == fingerprint)
+    }
+
+    pub fn record(&mut self, kind: &str, example: &str, fingerprint: &str) {
+        self.fingerprints
+            .insert(cache_key(kind, example), fingerprint.to_string());
+    }
+}
+
+/// `rtic/examples/<example>.rs`, where every example lives.
+pub fn example_source_path(example: &str) -> PathBuf {
+    Path::new("rtic/examples").join(format!("{example}.rs"))
+}
+
+fn hash_file(hasher: &mut DefaultHasher, path: &Path) {
+    if let Ok(contents) = fs::read(path) {
+        contents.hash(hasher);
+    }
+}
+
+/// Hash every `.rs` file under `dir`, in a stable (sorted) order so the
+/// fingerprint doesn't depend on directory-listing order.
+fn hash_dir(hasher: &mut DefaultHasher, dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut paths: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            hash_dir(hasher, &path);
+        } else if path.extension().and_then(|ext| ext.to_str()) == Some("rs") {
+            path.to_string_lossy().hash(hasher);
+            hash_file(hasher, &path);
+        }
+    }
+}
+
+/// Fingerprint one example: its own source, the `feature_key` describing the
+/// selected backend/features, and every shared crate's sources.
+pub fn fingerprint_example(example_path: &Path, feature_key: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    feature_key.hash(&mut hasher);
+    hash_file(&mut hasher, example_path);
+
+    for crate_name in SHARED_CRATES {
+        hash_dir(&mut hasher, &Path::new(crate_name).join("src"));
+    }
+
+    format!("{:016x}", hasher.finish())
+}
@@ -0,0 +1,312 @@
+//! Persistent binary-size history backing
+//! [`crate::cargo_commands::build_and_check_size`], turning its output into
+//! an actual regression gate instead of a one-shot report.
+//!
+//! Every run appends a [`SizeRecord`] per `(example, backend, target,
+//! section)` to a JSON history file, diffs it against a [`Baseline`] sample
+//! for that same key (the most recent one by default, or a specific commit
+//! via `--baseline-commit=<sha>`), and flags growth beyond a
+//! [`SizeThreshold`]. Samples are
+//! collected in a [`SizeCollector`] while examples build/size concurrently,
+//! then written once under a single lock instead of contending on the store.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::argument_parsing::ExtraArguments;
+
+/// One `(example, backend, target, section)` size sample.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SizeRecord {
+    pub example: String,
+    pub backend: String,
+    pub target: String,
+    pub section: String,
+    pub bytes: u64,
+    pub commit: String,
+    pub timestamp: u64,
+}
+
+impl SizeRecord {
+    pub fn new(example: &str, backend: &str, target: &str, section: &str, bytes: u64) -> Self {
+        Self {
+            example: example.to_string(),
+            backend: backend.to_string(),
+            target: target.to_string(),
+            section: section.to_string(),
+            bytes,
+            commit: current_commit(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        }
+    }
+
+    fn key(&self) -> (&str, &str, &str, &str) {
+        (&self.example, &self.backend, &self.target, &self.section)
+    }
+}
+
+fn current_commit() -> String {
+    Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|sha| sha.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Accumulates size samples across the parallel example pipeline so they can
+/// be written to disk once, instead of every worker locking the store.
+#[derive(Default)]
+pub struct SizeCollector {
+    records: Mutex<Vec<SizeRecord>>,
+}
+
+impl SizeCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, record: SizeRecord) {
+        self.records
+            .lock()
+            .expect("size collector mutex poisoned")
+            .push(record);
+    }
+
+    pub fn into_records(self) -> Vec<SizeRecord> {
+        self.records
+            .into_inner()
+            .expect("size collector mutex poisoned")
+    }
+}
+
+/// On-disk history of every recorded sample.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SizeHistory {
+    records: Vec<SizeRecord>,
+}
+
+/// Default location of the history file, at the workspace root.
+pub fn default_history_path() -> PathBuf {
+    PathBuf::from("size-history.json")
+}
+
+impl SizeHistory {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                serde_json::from_str(&contents).with_context(|| format!("failed to parse {path:?}"))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("failed to read {path:?}")),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents).with_context(|| format!("failed to write {path:?}"))
+    }
+
+    /// Sample recorded for this key that `baseline` selects, if any.
+    fn baseline(&self, key: (&str, &str, &str, &str), baseline: &Baseline) -> Option<&SizeRecord> {
+        match baseline {
+            Baseline::Latest => self.records.iter().rev().find(|r| r.key() == key),
+            Baseline::Commit(commit) => self
+                .records
+                .iter()
+                .rev()
+                .find(|r| r.key() == key && r.commit == *commit),
+        }
+    }
+}
+
+/// Which recorded sample a fresh size is diffed against.
+#[derive(Debug, Clone)]
+pub enum Baseline {
+    /// The most recently recorded sample for that key.
+    Latest,
+    /// The most recently recorded sample for that key stamped with a
+    /// specific commit, so a regression can be measured against a named
+    /// release rather than whatever ran last.
+    Commit(String),
+}
+
+impl Baseline {
+    /// Parsed out of an optional `--baseline-commit=<sha>` passthrough
+    /// argument; defaults to [`Baseline::Latest`] when it's absent.
+    pub fn from_extra_arguments(arguments: &Option<ExtraArguments>) -> Self {
+        arguments
+            .as_ref()
+            .and_then(|arguments| {
+                arguments
+                    .args
+                    .iter()
+                    .find_map(|arg| arg.strip_prefix("--baseline-commit=").map(str::to_string))
+            })
+            .map(Baseline::Commit)
+            .unwrap_or(Baseline::Latest)
+    }
+}
+
+/// Absolute and/or percentage growth beyond which a size sample is flagged
+/// as a regression.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SizeThreshold {
+    pub absolute_bytes: Option<u64>,
+    pub percent: Option<f64>,
+}
+
+impl SizeThreshold {
+    /// Parsed out of the `--deny-size-growth-abs=<bytes>` /
+    /// `--deny-size-growth-pct=<percent>` passthrough arguments.
+    pub fn from_extra_arguments(arguments: &Option<ExtraArguments>) -> Self {
+        let mut threshold = Self::default();
+        let Some(arguments) = arguments else {
+            return threshold;
+        };
+
+        for arg in &arguments.args {
+            if let Some(value) = arg.strip_prefix("--deny-size-growth-abs=") {
+                threshold.absolute_bytes = value.parse().ok();
+            } else if let Some(value) = arg.strip_prefix("--deny-size-growth-pct=") {
+                threshold.percent = value.parse().ok();
+            }
+        }
+
+        threshold
+    }
+
+    /// Recognized `--deny-size-growth-*` / `--baseline-commit=` control
+    /// flags, so callers can strip them before forwarding the rest of
+    /// `arguments` on to `cargo size` as genuine passthrough args.
+    fn is_control_flag(arg: &str) -> bool {
+        arg.starts_with("--deny-size-growth-abs=")
+            || arg.starts_with("--deny-size-growth-pct=")
+            || arg.starts_with("--baseline-commit=")
+    }
+
+    fn is_exceeded(&self, delta_bytes: i64, baseline_bytes: u64) -> bool {
+        if delta_bytes <= 0 {
+            return false;
+        }
+        let abs_violation = self
+            .absolute_bytes
+            .is_some_and(|max| delta_bytes as u64 > max);
+        let pct_violation = self.percent.is_some_and(|max_pct| {
+            baseline_bytes > 0 && (delta_bytes as f64 / baseline_bytes as f64) * 100.0 > max_pct
+        });
+        abs_violation || pct_violation
+    }
+}
+
+/// Strip the `--deny-size-growth-*` / `--baseline-commit=` control flags out
+/// of `arguments`, so what's left can be forwarded to the `cargo size`
+/// invocation as genuine passthrough args instead of corrupting it with
+/// flags `cargo size` doesn't understand.
+pub fn strip_control_flags(arguments: &Option<ExtraArguments>) -> Option<ExtraArguments> {
+    let mut arguments = arguments.clone()?;
+    arguments
+        .args
+        .retain(|arg| !SizeThreshold::is_control_flag(arg));
+    Some(arguments)
+}
+
+/// One row of the printed regression table.
+pub struct SizeDelta {
+    pub record: SizeRecord,
+    pub baseline_bytes: Option<u64>,
+    pub is_regression: bool,
+}
+
+/// Diff every freshly-collected sample against `history`'s previous sample
+/// for the same key, append the new samples to `history`, and return the
+/// deltas for reporting. `history` is only mutated here, once, after the
+/// parallel collection has already finished.
+pub fn record_and_diff(
+    history: &mut SizeHistory,
+    new_records: Vec<SizeRecord>,
+    threshold: &SizeThreshold,
+    baseline: &Baseline,
+) -> Vec<SizeDelta> {
+    let mut deltas = Vec::with_capacity(new_records.len());
+
+    for record in new_records {
+        let baseline_bytes = history.baseline(record.key(), baseline).map(|b| b.bytes);
+        let delta_bytes = baseline_bytes
+            .map(|baseline| record.bytes as i64 - baseline as i64)
+            .unwrap_or(0);
+        let is_regression = baseline_bytes
+            .map(|baseline| threshold.is_exceeded(delta_bytes, baseline))
+            .unwrap_or(false);
+
+        history.records.push(record.clone());
+        deltas.push(SizeDelta {
+            record,
+            baseline_bytes,
+            is_regression,
+        });
+    }
+
+    deltas
+}
+
+/// Parse the per-section sizes out of a `cargo size -A` (sysv format)
+/// report: one `<section> <bytes> <addr>` row per section.
+pub fn parse_sections(size_output: &str) -> HashMap<String, u64> {
+    const SECTIONS: &[&str] = &[".text", ".rodata", ".data", ".bss"];
+
+    size_output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let section = columns.next()?;
+            let bytes: u64 = columns.next()?.parse().ok()?;
+            SECTIONS
+                .contains(&section)
+                .then(|| (section.to_string(), bytes))
+        })
+        .collect()
+}
+
+/// Print a delta table to stdout: one row per sample, with the change
+/// against its baseline and a marker on regressions.
+pub fn print_table(deltas: &[SizeDelta]) {
+    println!(
+        "{:<28} {:<10} {:<10} {:<8} {:>10} {:>10}",
+        "example", "backend", "target", "section", "bytes", "delta"
+    );
+    for delta in deltas {
+        let delta_str = match delta.baseline_bytes {
+            Some(baseline) => format!("{:+}", delta.record.bytes as i64 - baseline as i64),
+            None => "new".to_string(),
+        };
+        let marker = if delta.is_regression {
+            "  !! regression"
+        } else {
+            ""
+        };
+        println!(
+            "{:<28} {:<10} {:<10} {:<8} {:>10} {:>10}{marker}",
+            delta.record.example,
+            delta.record.backend,
+            delta.record.target,
+            delta.record.section,
+            delta.record.bytes,
+            delta_str,
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! Snapshot comparison used by the `Qemu` command path in place of a strict
+//! byte-for-byte comparison of captured output against its expected file.
+//!
+//! Expected files may contain two escape hatches for the parts of Qemu
+//! output that are inherently nondeterministic:
+//!
+//! - `[..]` (or `[REDACTED]`, an alias for the same thing) is an intra-line
+//!   wildcard: the fragments on either side of it must still anchor the
+//!   start/end of the actual line, but anything can sit in between.
+//! - A `[UNORDERED-BEGIN]` / `[UNORDERED-END]` pair brackets a block of
+//!   lines whose relative order isn't guaranteed; the block matches as long
+//!   as every pattern line in it pairs with some actual line in that span.
+//!
+//! Hex addresses are normalized away before comparison, independently of
+//! `[..]`, since they show up unannotated in most Qemu traces.
+
+use std::fmt::Write as _;
+
+const UNORDERED_BEGIN: &str = "[UNORDERED-BEGIN]";
+const UNORDERED_END: &str = "[UNORDERED-END]";
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+/// Result of comparing captured Qemu output against an expected file.
+pub enum Comparison {
+    Match,
+    Mismatch { diff: String },
+}
+
+/// Compare `actual` against the matcher language in `expected`, returning a
+/// colored unified diff on mismatch.
+pub fn compare(expected: &str, actual: &str) -> Comparison {
+    let expected_lines: Vec<&str> = expected.lines().collect();
+    let actual_lines: Vec<&str> = actual.lines().collect();
+
+    if lines_match(&expected_lines, &actual_lines) {
+        Comparison::Match
+    } else {
+        Comparison::Mismatch {
+            diff: unified_diff(expected, actual),
+        }
+    }
+}
+
+/// Whether the expected file should be rewritten under `overwrite`: only
+/// when the normalized forms actually differ, not merely because the raw
+/// bytes differ (e.g. only a hex address moved).
+pub fn normalized_forms_differ(expected: &str, actual: &str) -> bool {
+    normalize(expected) != normalize(actual)
+}
+
+/// Strip hex addresses so otherwise-identical output doesn't appear to
+/// differ just because an address moved between runs.
+fn normalize(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(pos) = rest.find("0x") {
+        out.push_str(&rest[..pos]);
+        let digits_start = pos + 2;
+        let digits_end = rest[digits_start..]
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .map(|n| digits_start + n)
+            .unwrap_or(rest.len());
+        if digits_end > digits_start {
+            out.push_str("0x..");
+        } else {
+            out.push_str("0x");
+        }
+        rest = &rest[digits_end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn lines_match(expected: &[&str], actual: &[&str]) -> bool {
+    let mut e = 0;
+    let mut a = 0;
+
+    while e < expected.len() {
+        if expected[e] == UNORDERED_BEGIN {
+            e += 1;
+            let block_start = e;
+            while e < expected.len() && expected[e] != UNORDERED_END {
+                e += 1;
+            }
+            if e == expected.len() {
+                return false; // unterminated [UNORDERED-BEGIN]
+            }
+            let block = &expected[block_start..e];
+            e += 1; // skip [UNORDERED-END]
+
+            if actual.len() - a < block.len() {
+                return false;
+            }
+            let mut pool: Vec<&str> = actual[a..a + block.len()].to_vec();
+            for pattern in block {
+                match pool.iter().position(|line| line_matches(pattern, line)) {
+                    Some(idx) => {
+                        pool.remove(idx);
+                    }
+                    None => return false,
+                }
+            }
+            a += block.len();
+        } else {
+            if a >= actual.len() || !line_matches(expected[e], actual[a]) {
+                return false;
+            }
+            e += 1;
+            a += 1;
+        }
+    }
+
+    a == actual.len()
+}
+
+/// Does `actual` match the wildcard `pattern`, after normalizing both?
+fn line_matches(pattern: &str, actual: &str) -> bool {
+    let pattern = normalize(pattern).replace("[REDACTED]", "[..]");
+    let actual = normalize(actual);
+
+    let fragments: Vec<&str> = pattern.split("[..]").collect();
+    if fragments.len() == 1 {
+        return fragments[0] == actual;
+    }
+
+    let last = fragments.len() - 1;
+    let mut rest = actual.as_str();
+    for (i, fragment) in fragments.iter().enumerate() {
+        if i == 0 {
+            let Some(stripped) = rest.strip_prefix(fragment) else {
+                return false;
+            };
+            rest = stripped;
+        } else if i == last {
+            return rest.ends_with(fragment);
+        } else {
+            let Some(pos) = rest.find(fragment) else {
+                return false;
+            };
+            rest = &rest[pos + fragment.len()..];
+        }
+    }
+    true
+}
+
+enum DiffOp<'a> {
+    Equal(&'a str),
+    Remove(&'a str),
+    Insert(&'a str),
+}
+
+/// Line-based LCS diff between `old` and `new`.
+fn lcs_diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffOp<'a>> {
+    let (n, m) = (old.len(), new.len());
+    let mut lcs_len = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs_len[i][j] = if old[i] == new[j] {
+                lcs_len[i + 1][j + 1] + 1
+            } else {
+                lcs_len[i + 1][j].max(lcs_len[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            ops.push(DiffOp::Equal(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs_len[i + 1][j] >= lcs_len[i][j + 1] {
+            ops.push(DiffOp::Remove(old[i]));
+            i += 1;
+        } else {
+            ops.push(DiffOp::Insert(new[j]));
+            j += 1;
+        }
+    }
+    ops.extend(old[i..].iter().map(|line| DiffOp::Remove(line)));
+    ops.extend(new[j..].iter().map(|line| DiffOp::Insert(line)));
+    ops
+}
+
+/// Render a colored unified diff, collapsing equal runs down to `CONTEXT`
+/// lines of padding around each change.
+fn unified_diff(expected: &str, actual: &str) -> String {
+    const CONTEXT: usize = 3;
+
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+    let ops = lcs_diff(&old, &new);
+
+    let mut keep = vec![false; ops.len()];
+    for (idx, op) in ops.iter().enumerate() {
+        if !matches!(op, DiffOp::Equal(_)) {
+            let start = idx.saturating_sub(CONTEXT);
+            let end = (idx + CONTEXT + 1).min(ops.len());
+            keep[start..end].fill(true);
+        }
+    }
+
+    let mut out = String::new();
+    let mut in_gap = false;
+    for (idx, op) in ops.iter().enumerate() {
+        if !keep[idx] {
+            if !in_gap {
+                out.push_str("...\n");
+                in_gap = true;
+            }
+            continue;
+        }
+        in_gap = false;
+        match op {
+            DiffOp::Equal(line) => {
+                let _ = writeln!(out, "  {line}");
+            }
+            DiffOp::Remove(line) => {
+                let _ = writeln!(out, "{RED}- {line}{RESET}");
+            }
+            DiffOp::Insert(line) => {
+                let _ = writeln!(out, "{GREEN}+ {line}{RESET}");
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,212 @@
+//! UI / compile-fail test harness backing [`crate::cargo_commands::cargo_test_ui`].
+//!
+//! Each file under `tests/cfail` is compiled and its stderr checked two ways:
+//! inline `//~ ERROR <substring>` annotations (an annotation with N carets,
+//! e.g. `//~^^ ERROR ...`, attaches to the source line N lines above it;
+//! zero carets attaches to the annotation's own line), and a sibling
+//! `.stderr` snapshot compared with the same wildcard matcher `run_test`
+//! uses for Qemu output. `--bless` regenerates the snapshot, mirroring
+//! `run_test`'s `overwrite`.
+
+use std::{fs, path::Path};
+
+use crate::snapshot::{self, Comparison};
+
+/// One inline `//~ ERROR <substring>` expectation.
+struct InlineExpectation {
+    line: usize,
+    substring: String,
+}
+
+/// Parse every inline annotation out of a UI test file's source.
+///
+/// `//~ ERROR <substring>` (zero carets) attaches to the annotation's own
+/// line; `//~^ ERROR <substring>` (N carets) attaches to the line N above.
+fn parse_inline_expectations(source: &str) -> Vec<InlineExpectation> {
+    source
+        .lines()
+        .enumerate()
+        .filter_map(|(idx, line)| {
+            let rest = line.trim_start().strip_prefix("//~")?;
+            let carets = rest.chars().take_while(|&c| c == '^').count();
+            // `idx` is 0-indexed, so the annotation itself sits on source
+            // line `idx + 1`; a malformed annotation with more carets than
+            // lines above it has no valid target and is dropped rather than
+            // underflowing.
+            let line = (idx + 1).checked_sub(carets)?;
+            let substring = rest[carets..].trim_start().strip_prefix("ERROR")?.trim();
+            Some(InlineExpectation {
+                line,
+                substring: substring.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// One compiler diagnostic, reduced to what an inline expectation checks.
+struct Diagnostic {
+    line: usize,
+    message: String,
+}
+
+/// Parse `error[...]: <message>` / `--> file:line:col` pairs out of rustc's
+/// human-readable stderr.
+fn parse_diagnostics(stderr: &str) -> Vec<Diagnostic> {
+    let lines: Vec<&str> = stderr.lines().collect();
+    let mut diagnostics = vec![];
+
+    for (idx, line) in lines.iter().enumerate() {
+        if !(line.starts_with("error") || line.starts_with("warning")) {
+            continue;
+        }
+        let Some((_, message)) = line.split_once(": ") else {
+            continue;
+        };
+
+        let location = lines[idx + 1..(idx + 4).min(lines.len())]
+            .iter()
+            .find_map(|l| l.trim_start().strip_prefix("--> "));
+        let Some(line_no) = location.and_then(|loc| loc.split(':').nth(1)?.parse().ok()) else {
+            continue;
+        };
+
+        diagnostics.push(Diagnostic {
+            line: line_no,
+            message: message.to_string(),
+        });
+    }
+
+    diagnostics
+}
+
+/// Check every inline annotation against the diagnostics actually emitted,
+/// returning one failure string per unmet expectation.
+fn check_inline(path: &Path, source: &str, stderr: &str) -> Vec<String> {
+    let diagnostics = parse_diagnostics(stderr);
+
+    parse_inline_expectations(source)
+        .into_iter()
+        .filter(|expectation| {
+            !diagnostics
+                .iter()
+                .any(|d| d.line == expectation.line && d.message.contains(&expectation.substring))
+        })
+        .map(|expectation| {
+            format!(
+                "{}: expected an ERROR containing {:?} on line {}, found none",
+                path.display(),
+                expectation.substring,
+                expectation.line
+            )
+        })
+        .collect()
+}
+
+/// Drop the parts of rustc's stderr that drift across toolchains: absolute
+/// paths collapse to the file name, and its `line:col` collapses to a
+/// placeholder; the "for more information" footer (which embeds the
+/// compiler's error-index version) is dropped outright.
+fn normalize_for_snapshot(stderr: &str, source_path: &Path) -> String {
+    let file_name = source_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+
+    let mut normalized = String::with_capacity(stderr.len());
+    for line in stderr.lines() {
+        if line
+            .trim_start()
+            .starts_with("For more information about this error")
+        {
+            continue;
+        }
+        normalized.push_str(&normalize_location(line, file_name));
+        normalized.push('\n');
+    }
+    normalized
+}
+
+/// Replace a `<anything>/<file_name>:<line>:<col>` occurrence with
+/// `<file_name>:LINE:COL`.
+fn normalize_location(line: &str, file_name: &str) -> String {
+    if file_name.is_empty() {
+        return line.to_string();
+    }
+    let Some(marker) = line.find(file_name) else {
+        return line.to_string();
+    };
+
+    let path_start = line[..marker]
+        .rfind(char::is_whitespace)
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let after_name = &line[marker + file_name.len()..];
+
+    let mut rest = after_name;
+    let mut consumed = 0;
+    for _ in 0..2 {
+        let Some(digits) = rest.strip_prefix(':') else {
+            break;
+        };
+        let digit_len = digits
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(digits.len());
+        consumed += 1 + digit_len;
+        rest = &digits[digit_len..];
+    }
+
+    format!(
+        "{}{file_name}:LINE:COL{}",
+        &line[..path_start],
+        &after_name[consumed..]
+    )
+}
+
+/// Compare the normalized stderr against the sibling `.stderr` snapshot,
+/// writing it under `bless` when it's missing or out of date. Returns a diff
+/// string on an un-blessed mismatch.
+fn check_snapshot(path: &Path, normalized: &str, bless: bool) -> anyhow::Result<Option<String>> {
+    let snapshot_path = path.with_extension("stderr");
+
+    let Ok(expected) = fs::read_to_string(&snapshot_path) else {
+        if bless {
+            fs::write(&snapshot_path, normalized)?;
+        }
+        return Ok(None);
+    };
+
+    match snapshot::compare(&expected, normalized) {
+        Comparison::Match => Ok(None),
+        Comparison::Mismatch { diff } => {
+            if bless {
+                if snapshot::normalized_forms_differ(&expected, normalized) {
+                    fs::write(&snapshot_path, normalized)?;
+                }
+                Ok(None)
+            } else {
+                Ok(Some(diff))
+            }
+        }
+    }
+}
+
+/// Run both checks for one UI test file's captured stderr, returning every
+/// failure found (empty when the test passed).
+pub fn run_ui_test(
+    path: &Path,
+    source: &str,
+    stderr: &str,
+    bless: bool,
+) -> anyhow::Result<Vec<String>> {
+    let mut failures = check_inline(path, source, stderr);
+
+    let normalized = normalize_for_snapshot(stderr, path);
+    if let Some(diff) = check_snapshot(path, &normalized, bless)? {
+        failures.push(format!(
+            "{}: stderr snapshot mismatch\n{diff}",
+            path.display()
+        ));
+    }
+
+    Ok(failures)
+}